@@ -1,18 +1,23 @@
 use std::cell::RefCell;
-use std::io::{Error, Result};
+use std::io::{BufRead, Error, ErrorKind, Result};
+use std::ops::Range;
 
-use crate::{Position, Span};
+use crate::{DefaultMetrics, Metrics, Position, Span};
 
 /// Lazy string buffer that fills up on demand.
 ///
 /// The `lazy::Buffer` wraps aroung a `char` iterator. It can be itself used as
 /// a `char` iterator, or as a `Buffer` to access an arbitrary fragment of the
 /// input source stream.
-pub struct Buffer<I: Iterator<Item = Result<char>>> {
-    p: RefCell<Inner<I>>,
+///
+/// The cursor metrics used to compute positions default to [`DefaultMetrics`].
+/// Use [`new_with_metrics`](Buffer::new_with_metrics) to pick another
+/// [`Metrics`] instance.
+pub struct Buffer<I: Iterator<Item = Result<char>>, M: Metrics = DefaultMetrics> {
+    p: RefCell<Inner<I, M>>,
 }
 
-struct Inner<I: Iterator<Item = Result<char>>> {
+struct Inner<I: Iterator<Item = Result<char>>, M: Metrics> {
     /// Input source `char` stream.
     input: I,
 
@@ -22,16 +27,63 @@ struct Inner<I: Iterator<Item = Result<char>>> {
     /// The buffer data.
     data: Vec<char>,
 
+    /// Absolute byte offset of each buffered character.
+    ///
+    /// Parallel to [`data`](Inner::data): `byte_offsets[i]` is the offset, in
+    /// bytes, at which the UTF-8 encoding of `data[i]` begins in the source
+    /// stream. Accumulated as each character is pushed and kept consistent
+    /// across trims and rewinds, so a character span can be mapped back to a
+    /// byte range of the original input.
+    byte_offsets: Vec<usize>,
+
     /// Lines index.
     ///
-    /// Contains the index of the first character of each line.
+    /// Contains the index of the first character of each buffered line,
+    /// relative to the start of [`data`](Inner::data). Leading entries are
+    /// dropped when the buffer is trimmed to its window.
     lines: Vec<usize>,
 
+    /// Byte offset of the first character of each line ever read.
+    ///
+    /// Unlike [`lines`](Inner::lines), this is indexed by absolute line number
+    /// (offset by [`first_line`](Inner::first_line)) and is never trimmed, so a
+    /// line that scrolled out of the window can still be located in the source
+    /// and re-decoded.
+    line_offsets: Vec<usize>,
+
+    /// Byte offset of the next character to read from the input stream.
+    byte_pos: usize,
+
+    /// Line number of the first character ever read.
+    first_line: usize,
+
+    /// Number of characters dropped from the front of [`data`](Inner::data).
+    ///
+    /// Trimming shifts every surviving element of `data` to the left, which
+    /// would invalidate any index held by a live [`Iter`]. Indices are
+    /// therefore expressed *logically*, relative to the first character ever
+    /// buffered; subtracting `dropped` recovers the physical position in
+    /// `data`. A rewind that splices characters back in front decreases it
+    /// again, so a logical index survives both trims and rewinds.
+    dropped: usize,
+
     /// The span of the buffer.
     span: Span,
+
+    /// Factory reproducing the input `char` stream from a byte offset.
+    ///
+    /// Present only for rewindable buffers. It lets the buffer re-decode lines
+    /// that have been trimmed to honor the window.
+    factory: Option<Box<dyn FnMut(usize) -> I>>,
+
+    /// Maximum number of lines kept in the buffer, if bounded.
+    window: Option<usize>,
+
+    /// Cursor metrics used to advance the buffer span.
+    metrics: M,
 }
 
-impl<I: Iterator<Item = Result<char>>> Inner<I> {
+impl<I: Iterator<Item = Result<char>>, M: Metrics> Inner<I, M> {
     /// Read the next line from the input stream and add it to the buffer.
     /// Returns `true` if a new line has been added. Returns `false` if the
     /// source stream is done.
@@ -41,8 +93,10 @@ impl<I: Iterator<Item = Result<char>>> Inner<I> {
             while line == self.span.end().line {
                 match self.input.next() {
                     Some(Ok(c)) => {
+                        self.byte_offsets.push(self.byte_pos);
                         self.data.push(c);
-                        self.span.push(c);
+                        self.byte_pos += c.len_utf8();
+                        self.span.push(c, &self.metrics);
                     }
                     Some(Err(e)) => {
                         self.error = Some(e);
@@ -55,12 +109,138 @@ impl<I: Iterator<Item = Result<char>>> Inner<I> {
             // register the next line index.
             self.lines.push(self.data.len());
 
+            // register the byte offset of the line that just started, if it
+            // has not been seen before.
+            let relative_line = self.span.end().line - self.first_line;
+            if relative_line >= self.line_offsets.len() {
+                self.line_offsets.push(self.byte_pos);
+            }
+
+            self.trim_to_window();
+
             true
         } else {
             false
         }
     }
 
+    /// Drop leading lines until the buffer holds at most `window` lines.
+    ///
+    /// Has no effect on an unbounded buffer. The dropped characters can still
+    /// be recovered through [`rewind_to`](Inner::rewind_to) as long as the
+    /// buffer was built with a stream factory.
+    fn trim_to_window(&mut self) {
+        let window = match self.window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let mut dropped = 0;
+        // `lines` ends with the index of the line currently being filled, so
+        // the number of complete buffered lines is `lines.len() - 1`.
+        while self.lines.len() > window + 1 {
+            let boundary = self.lines[1];
+            self.data.drain(0..boundary);
+            self.byte_offsets.drain(0..boundary);
+            self.lines.remove(0);
+            for i in &mut self.lines {
+                *i -= boundary;
+            }
+            // Keep logical indices valid across the left-shift.
+            self.dropped += boundary;
+            dropped += 1;
+        }
+
+        if dropped > 0 {
+            let start = Position::new(self.span.start().line + dropped, 0);
+            self.rebuild_span(start);
+        }
+    }
+
+    /// Rebuild the buffer span from `start`, folding over the buffered data.
+    ///
+    /// Used after the buffer bounds change (trimming or rewinding), where
+    /// `start` is the position of the first buffered character.
+    fn rebuild_span(&mut self, start: Position) {
+        let mut span: Span = start.into();
+        for &c in &self.data {
+            span.push(c, &self.metrics);
+        }
+        self.span = span;
+    }
+
+    /// Re-decode the lines in `target_line..span.start().line` and splice them
+    /// back in front of the buffer, so a position that scrolled out of the
+    /// window becomes addressable again.
+    ///
+    /// Only available on a rewindable buffer; a plain buffer cannot reproduce
+    /// trimmed input and the caller returns `None` instead.
+    fn rewind_to(&mut self, target_line: usize) -> Result<()> {
+        let start_line = self.span.start().line;
+        if target_line >= start_line {
+            return Ok(());
+        }
+
+        let offset = self.line_offsets[target_line - self.first_line];
+        let mut input = match self.factory.as_mut() {
+            Some(factory) => factory(offset),
+            None => return Ok(()),
+        };
+
+        let mut prefix = Vec::new();
+        let mut prefix_offsets = Vec::new();
+        let mut prefix_lines = vec![0];
+        let mut byte_cursor = offset;
+        let mut cursor = Position::new(target_line, 0);
+        while cursor.line < start_line {
+            match input.next() {
+                Some(Ok(c)) => {
+                    prefix.push(c);
+                    prefix_offsets.push(byte_cursor);
+                    byte_cursor += c.len_utf8();
+                    let next = cursor.next_metrics(c, &self.metrics);
+                    if next.line != cursor.line {
+                        prefix_lines.push(prefix.len());
+                    }
+                    cursor = next;
+                }
+                Some(Err(e)) => return Err(e),
+                None => break,
+            }
+        }
+
+        if cursor.line < start_line {
+            // The factory produced a shorter stream than before: leave the
+            // buffer untouched rather than splicing inconsistent data.
+            return Ok(());
+        }
+
+        // The last recorded line start coincides with the (shifted) former
+        // buffer start, so it is dropped to avoid a duplicate entry.
+        prefix_lines.pop();
+
+        let shift = prefix.len();
+        for i in &mut self.lines {
+            *i += shift;
+        }
+        // The re-spliced characters were dropped earlier, so they no longer
+        // count against the logical base.
+        self.dropped -= shift;
+
+        prefix.extend_from_slice(&self.data);
+        self.data = prefix;
+        // Byte offsets are absolute, so the re-decoded prefix simply slots in
+        // front of the surviving offsets without any shift.
+        prefix_offsets.extend_from_slice(&self.byte_offsets);
+        self.byte_offsets = prefix_offsets;
+        prefix_lines.extend_from_slice(&self.lines);
+        self.lines = prefix_lines;
+
+        self.rebuild_span(Position::new(target_line, 0));
+
+        Ok(())
+    }
+
     /// Get the index of the char at the given cursor position if it is in the
     /// buffer. If it is not in the buffer but after the buffered content,
     /// the input stream will be read until the buffer span includes the
@@ -72,51 +252,26 @@ impl<I: Iterator<Item = Result<char>>> Inner<I> {
     /// column.
     fn index_at(&mut self, pos: Position) -> Option<Result<usize>> {
         if pos < self.span.start() {
-            None
-        } else {
-            while pos >= self.span.end() && self.read_line() {}
-
-            if pos >= self.span.end() {
-                let mut error = None;
-                std::mem::swap(&mut error, &mut self.error);
-                match error {
-                    Some(e) => Some(Err(e)),
-                    None => None,
-                }
-            } else {
-                // line index relative to the first line of the buffer.
-                let relative_line = pos.line - self.span.start().line;
-                // get the index of the char of the begining of the line in the buffer.
-                let mut i = self.lines[relative_line];
-                // place a virtual cursor at the begining of the target line.
-                let mut cursor = Position::new(pos.line, 0);
-
-                while cursor < pos {
-                    cursor = cursor.next(self.data[i]);
-                    i += 1;
-                }
+            // The position was trimmed out of the window. If the buffer can
+            // reproduce its input, re-decode the missing lines; otherwise the
+            // position is gone for good.
+            if self.factory.is_none() || pos.line < self.first_line {
+                return None;
+            }
+            if let Err(e) = self.rewind_to(pos.line) {
+                return Some(Err(e));
+            }
 
-                if cursor == pos {
-                    // found it!
-                    Some(Ok(i))
-                } else {
-                    // the position does not exist in the buffer.
-                    None
-                }
+            // The rewind cannot reach a position that lies before the buffer
+            // start on its own line (there is no earlier line to recover).
+            if pos < self.span.start() {
+                return None;
             }
         }
-    }
 
-    /// Get the character at the given index.
-    ///
-    /// If it is not in the buffer but after the buffered content, the input
-    /// stream will be read until the buffer span includes the given
-    /// position. Returns `None` if the source stream ends before the given
-    /// position.
-    fn get(&mut self, i: usize) -> Option<Result<char>> {
-        while i >= self.data.len() && self.read_line() {}
+        while pos >= self.span.end() && self.read_line() {}
 
-        if i >= self.data.len() {
+        if pos >= self.span.end() {
             let mut error = None;
             std::mem::swap(&mut error, &mut self.error);
             match error {
@@ -124,21 +279,286 @@ impl<I: Iterator<Item = Result<char>>> Inner<I> {
                 None => None,
             }
         } else {
-            Some(Ok(self.data[i]))
+            // line index relative to the first line of the buffer.
+            let relative_line = pos.line - self.span.start().line;
+            // get the index of the char of the begining of the line in the buffer.
+            let mut i = self.lines[relative_line];
+            // place a virtual cursor at the begining of the target line.
+            let mut cursor = Position::new(pos.line, 0);
+
+            while cursor < pos {
+                cursor = cursor.next_metrics(self.data[i], &self.metrics);
+                i += 1;
+            }
+
+            if cursor == pos {
+                // found it! Report the index logically so it survives a trim.
+                Some(Ok(i + self.dropped))
+            } else {
+                // the position does not exist in the buffer.
+                None
+            }
         }
     }
+
+    /// Resolve a span into the full text of every source line it touches.
+    ///
+    /// The span is expanded outward to line boundaries: the first line starts
+    /// at column `0` and the last line continues until its end-of-line index.
+    /// The returned [`ResolvedSpan`] therefore carries whole lines, together
+    /// with the column offsets marking the span interior within the first and
+    /// last lines.
+    fn resolve(&mut self, span: Span) -> Result<ResolvedSpan> {
+        let start_line = span.start().line;
+
+        // Re-decode lines that scrolled out of the window, so a span whose
+        // start was trimmed can still be resolved in full.
+        if span.start() < self.span.start() && self.factory.is_some() && start_line >= self.first_line
+        {
+            self.rewind_to(start_line)?;
+        }
+
+        // A span ending at the very start of a line (column `0`) does not
+        // actually cover that line, unless it is empty.
+        let mut last_line = if span.end().line > start_line && span.end().column == 0 {
+            span.end().line - 1
+        } else {
+            span.end().line
+        };
+
+        // Buffer every line the span touches. Trimming is suspended so that no
+        // covered line is dropped before the whole span has been collected.
+        let window = self.window.take();
+        while self.span.end().line <= last_line && self.read_line() {}
+        self.window = window;
+
+        let buf_start_line = self.span.start().line;
+        // Positions before the buffer start cannot be resolved, so the first
+        // covered line is clamped to the buffer start.
+        let first_line = start_line.max(buf_start_line);
+
+        if let Some(e) = self.error.take() {
+            if self.span.end().line <= last_line {
+                return Err(e);
+            }
+            // The error lies past the requested span: leave it in place for
+            // later reads.
+            self.error = Some(e);
+        }
+
+        // Clamp to the last line actually buffered in case the span runs past
+        // the end of the input stream.
+        let max_line = buf_start_line + self.lines.len().saturating_sub(1);
+        last_line = last_line.min(max_line);
+
+        let mut lines = Vec::new();
+        let mut end_col = span.end().column;
+
+        for line in first_line..=last_line {
+            let relative_line = line - buf_start_line;
+            let start = self.lines[relative_line];
+            let mut end = self
+                .lines
+                .get(relative_line + 1)
+                .copied()
+                .unwrap_or(self.data.len());
+
+            // Drop the trailing line terminator from the line text.
+            if end > start && self.data[end - 1] == '\n' {
+                end -= 1;
+                if end > start && self.data[end - 1] == '\r' {
+                    end -= 1;
+                }
+            }
+
+            let mut text = String::new();
+            let mut line_span: Span = Position::new(line, 0).into();
+            for &c in &self.data[start..end] {
+                text.push(c);
+                line_span.push(c, &self.metrics);
+            }
+
+            if line == last_line && last_line != span.end().line {
+                // The span ran onto the start of the following line, so the
+                // end column is the end of this, the last covered, line.
+                end_col = line_span.end().column;
+            }
+
+            lines.push(SourceLine { span: line_span, text });
+        }
+
+        Ok(ResolvedSpan {
+            span,
+            lines,
+            // If the start was clamped to the buffer, the first covered line
+            // begins at its own start rather than at the span start column.
+            start_col: if start_line >= buf_start_line {
+                span.start().column
+            } else {
+                0
+            },
+            end_col,
+        })
+    }
+
+    /// Get the character at the given logical index.
+    ///
+    /// The index is the one returned by [`index_at`](Inner::index_at): it is
+    /// relative to the first character ever buffered, so it stays valid even
+    /// after a trim shifts [`data`](Inner::data). If the character is not yet
+    /// buffered, the input stream is read until it is. Returns `None` if the
+    /// source stream ends before the index, or if the character has already
+    /// scrolled out of the window.
+    fn get(&mut self, index: usize) -> Option<Result<char>> {
+        loop {
+            // Translate the logical index into the current physical position.
+            match index.checked_sub(self.dropped) {
+                Some(physical) if physical < self.data.len() => {
+                    return Some(Ok(self.data[physical]));
+                }
+                // The character scrolled out of the window for good.
+                None => return None,
+                Some(_) => {}
+            }
+
+            if !self.read_line() {
+                let mut error = None;
+                std::mem::swap(&mut error, &mut self.error);
+                return match error {
+                    Some(e) => Some(Err(e)),
+                    None => None,
+                };
+            }
+        }
+    }
+
+    /// Get the byte offset of the character at the given cursor position.
+    ///
+    /// Behaves like [`index_at`](Inner::index_at) but returns the offset, in
+    /// bytes, at which the character begins in the source stream rather than
+    /// its index in the buffer.
+    fn byte_index_at(&mut self, pos: Position) -> Option<Result<usize>> {
+        match self.index_at(pos) {
+            // `index_at` reports a logical index; subtract the dropped base to
+            // read the byte offset from the physical table.
+            Some(Ok(i)) => Some(Ok(self.byte_offsets[i - self.dropped])),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        }
+    }
+
+    /// Map a character span to its byte range in the source stream.
+    fn byte_span(&mut self, span: Span) -> Option<Range<usize>> {
+        let start = match self.byte_index_at(span.start()) {
+            Some(Ok(start)) => start,
+            _ => return None,
+        };
+
+        let end = match self.index_at(span.end()) {
+            Some(Ok(i)) => self.byte_offsets[i - self.dropped],
+            Some(Err(_)) => return None,
+            // The span ends at the end of the input: it runs to the last byte
+            // read from the stream.
+            None if span.end() == self.span.end() && self.error.is_none() => self.byte_pos,
+            None => return None,
+        };
+
+        Some(start..end)
+    }
 }
 //
-impl<I: Iterator<Item = Result<char>>> Buffer<I> {
+impl<I: Iterator<Item = Result<char>>> Buffer<I, DefaultMetrics> {
     /// Create a new empty buffer starting at the given position.
+    ///
+    /// The buffer uses the [`DefaultMetrics`] cursor metrics. Use
+    /// [`new_with_metrics`](Buffer::new_with_metrics) to pick another one.
     pub fn new(input: I, position: Position) -> Self {
+        Self::new_with_metrics(input, position, DefaultMetrics)
+    }
+}
+
+impl<R: BufRead> Buffer<Chars<R>, DefaultMetrics> {
+    /// Create a new empty buffer decoding UTF-8 from a byte reader.
+    ///
+    /// Bytes are pulled from `input` on demand and decoded into `char`s
+    /// incrementally, so a multi-byte sequence split across read boundaries is
+    /// completed rather than erroring. A malformed sequence is surfaced as an
+    /// [`std::io::Error`] through the buffer error state, just like any other
+    /// read error.
+    ///
+    /// Use [`from_read_lossy`](Buffer::from_read_lossy) to substitute the
+    /// U+FFFD replacement character instead of failing.
+    pub fn from_read(input: R, position: Position) -> Self {
+        Self::new(Chars::new(input, false), position)
+    }
+
+    /// Create a new empty buffer decoding UTF-8 from a byte reader, replacing
+    /// malformed sequences with the U+FFFD replacement character.
+    ///
+    /// This behaves like [`from_read`](Buffer::from_read) except that a
+    /// malformed sequence yields `'\u{FFFD}'` rather than an error.
+    pub fn from_read_lossy(input: R, position: Position) -> Self {
+        Self::new(Chars::new(input, true), position)
+    }
+}
+
+impl<I: Iterator<Item = Result<char>>, M: Metrics> Buffer<I, M> {
+    /// Create a new empty buffer starting at the given position, using the
+    /// given cursor metrics to compute positions.
+    pub fn new_with_metrics(input: I, position: Position, metrics: M) -> Self {
+        Self {
+            p: RefCell::new(Inner {
+                input,
+                error: None,
+                data: Vec::new(),
+                byte_offsets: Vec::new(),
+                lines: vec![0],
+                line_offsets: vec![0],
+                byte_pos: 0,
+                first_line: position.line,
+                dropped: 0,
+                span: position.into(),
+                factory: None,
+                window: None,
+                metrics,
+            }),
+        }
+    }
+
+    /// Create a new rewindable, memory-bounded buffer starting at the given
+    /// position.
+    ///
+    /// Instead of a single `char` iterator, this takes a `factory` that
+    /// reproduces the input `char` stream starting at an arbitrary byte offset
+    /// — typically by seeking the underlying reader. The initial stream is
+    /// obtained with `factory(0)`.
+    ///
+    /// At most `window` lines are kept in memory: once that many lines have
+    /// been buffered, reading ahead drops the leading lines. A position that
+    /// scrolled out of the window is not lost — the next [`at`](Buffer::at) or
+    /// [`index_at`](Buffer::index_at) for it rewinds the stream to the stored
+    /// byte offset of its line and re-decodes forward, so a span read twice
+    /// yields identical characters.
+    pub fn new_rewindable<F>(mut factory: F, position: Position, window: usize, metrics: M) -> Self
+    where
+        F: FnMut(usize) -> I + 'static,
+    {
+        let input = factory(0);
         Self {
             p: RefCell::new(Inner {
                 input,
                 error: None,
                 data: Vec::new(),
+                byte_offsets: Vec::new(),
                 lines: vec![0],
+                line_offsets: vec![0],
+                byte_pos: 0,
+                first_line: position.line,
+                dropped: 0,
                 span: position.into(),
+                factory: Some(Box::new(factory)),
+                window: Some(window),
+                metrics,
             }),
         }
     }
@@ -159,6 +579,44 @@ impl<I: Iterator<Item = Result<char>>> Buffer<I> {
         self.p.borrow_mut().index_at(pos)
     }
 
+    /// Get the byte offset of the character at the given cursor position.
+    ///
+    /// Like [`index_at`](Buffer::index_at), but returns the offset, in bytes,
+    /// at which the character's UTF-8 encoding begins in the source stream.
+    /// This lets a consumer holding the raw source bytes slice out the exact
+    /// substring a [`Span`] covers without re-walking characters.
+    ///
+    /// Returns `None` under the same conditions as [`index_at`](Buffer::index_at).
+    pub fn byte_index_at(&self, pos: Position) -> Option<Result<usize>> {
+        self.p.borrow_mut().byte_index_at(pos)
+    }
+
+    /// Map a character span to the range of byte offsets it covers in the
+    /// source stream, so that `&original[buffer.byte_span(span)?]` recovers the
+    /// exact substring.
+    ///
+    /// The input stream is read on demand until the span is buffered. Returns
+    /// `None` if either span boundary falls outside the addressable buffer, or
+    /// if reading raised an error.
+    pub fn byte_span(&self, span: Span) -> Option<Range<usize>> {
+        self.p.borrow_mut().byte_span(span)
+    }
+
+    /// Resolve a span into the full text of every source line it touches.
+    ///
+    /// Unlike [`iter_span`](Buffer::iter_span), which yields only the span
+    /// interior, this expands the span outward to line boundaries so that the
+    /// returned [`ResolvedSpan`] holds every source line the span covers in
+    /// full, together with the column offsets of the span start and end. This
+    /// is everything needed to render `rustc`-style underlined annotations
+    /// without re-reading the input.
+    ///
+    /// The input stream is read on demand until the span is fully buffered.
+    /// Any [`std::io::Error`] raised while reading is returned.
+    pub fn resolve(&self, span: Span) -> Result<ResolvedSpan> {
+        self.p.borrow_mut().resolve(span)
+    }
+
     /// Get the char at the given position if it is in the buffer.
     /// If it is not in the buffer but after the buffered content, the input
     /// stream will be read until the buffer span includes the given
@@ -189,7 +647,7 @@ impl<I: Iterator<Item = Result<char>>> Buffer<I> {
     ///
     /// When it reaches the end of the buffer, the buffer will start reading
     /// from the source stream.
-    pub fn iter(&self) -> Iter<I> {
+    pub fn iter(&self) -> Iter<I, M> {
         Iter {
             buffer: self,
             i: Some(Ok(0)),
@@ -205,7 +663,7 @@ impl<I: Iterator<Item = Result<char>>> Buffer<I> {
     /// start from the buffer start position.
     /// When it reaches the end of the buffer, the buffer will start reading
     /// from the source stream.
-    pub fn iter_from(&self, pos: Position) -> Iter<I> {
+    pub fn iter_from(&self, pos: Position) -> Iter<I, M> {
         let start = self.p.borrow().span.start();
         let pos = std::cmp::max(start, pos);
 
@@ -224,7 +682,7 @@ impl<I: Iterator<Item = Result<char>>> Buffer<I> {
     /// will start from the buffer start position.
     /// When it reaches the end of the buffer, the buffer will start reading
     /// from the source stream.
-    pub fn iter_span(&self, span: Span) -> Iter<I> {
+    pub fn iter_span(&self, span: Span) -> Iter<I, M> {
         let start = self.p.borrow().span.start();
         let pos = std::cmp::max(start, span.start());
 
@@ -237,20 +695,192 @@ impl<I: Iterator<Item = Result<char>>> Buffer<I> {
     }
 }
 
+/// A single source line covered by a [`Span`], as returned by
+/// [`Buffer::resolve`].
+pub struct SourceLine {
+    /// Span of the whole line, from column `0` to the end of the line.
+    pub span: Span,
+
+    /// Text of the line, without its trailing line terminator.
+    pub text: String,
+}
+
+/// The source lines covered by a [`Span`], as returned by [`Buffer::resolve`].
+///
+/// The `lines` field holds every source line the span touches, in full. The
+/// `start_col` and `end_col` fields mark the span interior: the starting column
+/// within the first line and the ending column within the last line. A
+/// zero-width span at the end of a line resolves to a single line with
+/// `start_col == end_col`.
+pub struct ResolvedSpan {
+    /// The resolved span.
+    pub span: Span,
+
+    /// The source lines the span touches, in order.
+    pub lines: Vec<SourceLine>,
+
+    /// Column offset of the span start within the first line.
+    pub start_col: usize,
+
+    /// Column offset of the span end within the last line.
+    pub end_col: usize,
+}
+
+/// Incremental UTF-8 decoder over a byte reader.
+///
+/// `Chars` turns a byte reader into a `char` iterator suitable for a
+/// [`Buffer`], pulling bytes on demand and decoding them one code point at a
+/// time. A multi-byte sequence straddling a read boundary is held until the
+/// reader yields its remaining bytes, so no valid input is ever rejected.
+///
+/// A malformed sequence is either surfaced as an [`std::io::Error`] or replaced
+/// by the U+FFFD replacement character, depending on the `lossy` flag chosen at
+/// construction. Once an error is surfaced the iterator is fused and returns
+/// `None`.
+pub struct Chars<R: BufRead> {
+    input: R,
+    lossy: bool,
+    done: bool,
+}
+
+impl<R: BufRead> Chars<R> {
+    /// Create a new decoder over the given byte reader.
+    ///
+    /// If `lossy` is `true`, malformed sequences are replaced by U+FFFD;
+    /// otherwise they are surfaced as an [`std::io::Error`].
+    pub fn new(input: R, lossy: bool) -> Self {
+        Self { input, lossy, done: false }
+    }
+
+    /// Peek at the next byte without consuming it, retrying on interruption.
+    ///
+    /// Returns `None` at the end of the stream. The byte stays in the reader
+    /// until [`bump`](Chars::bump) is called, so a byte that turns out not to
+    /// belong to the current sequence is left for the next decode.
+    fn peek_byte(&mut self) -> Option<Result<u8>> {
+        loop {
+            return match self.input.fill_buf() {
+                Ok(buf) => match buf.first().copied() {
+                    Some(b) => Some(Ok(b)),
+                    None => None,
+                },
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Some(Err(e)),
+            };
+        }
+    }
+
+    /// Consume the byte last returned by [`peek_byte`](Chars::peek_byte).
+    fn bump(&mut self) {
+        self.input.consume(1);
+    }
+
+    /// Report a malformed sequence, either as an error or as U+FFFD.
+    fn invalid(&mut self) -> Option<Result<char>> {
+        if self.lossy {
+            Some(Ok('\u{FFFD}'))
+        } else {
+            self.done = true;
+            Some(Err(Error::new(ErrorKind::InvalidData, "invalid UTF-8 sequence")))
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Chars<R> {
+    type Item = Result<char>;
+
+    fn next(&mut self) -> Option<Result<char>> {
+        if self.done {
+            return None;
+        }
+
+        let lead = match self.peek_byte() {
+            Some(Ok(b)) => b,
+            Some(Err(e)) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+            None => return None,
+        };
+
+        // Length of the sequence and the accepted range of its *second* byte,
+        // derived from the leading byte. The restricted second-byte ranges
+        // reject overlong encodings (`E0`, `F0`) and surrogates (`ED`) up
+        // front; every later continuation byte accepts the full `0x80..=0xBF`.
+        // A leading byte that cannot start any sequence falls through to the
+        // malformed path below.
+        let (len, second) = match lead {
+            0x00..=0x7F => {
+                self.bump();
+                return Some(Ok(lead as char));
+            }
+            0xC2..=0xDF => (2, 0x80..=0xBF),
+            0xE0 => (3, 0xA0..=0xBF),
+            0xE1..=0xEC => (3, 0x80..=0xBF),
+            0xED => (3, 0x80..=0x9F),
+            0xEE..=0xEF => (3, 0x80..=0xBF),
+            0xF0 => (4, 0x90..=0xBF),
+            0xF1..=0xF3 => (4, 0x80..=0xBF),
+            0xF4 => (4, 0x80..=0x8F),
+            _ => {
+                // The leading byte is invalid on its own: consume just it, so
+                // any following valid bytes are still decoded.
+                self.bump();
+                return self.invalid();
+            }
+        };
+
+        let mut bytes = [0u8; 4];
+        bytes[0] = lead;
+        self.bump();
+
+        for i in 1..len {
+            // The first continuation byte honors the restricted `second` range;
+            // the rest accept the full continuation range.
+            let range = if i == 1 { second.clone() } else { 0x80..=0xBF };
+
+            let byte = match self.peek_byte() {
+                Some(Ok(b)) => b,
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                // Truncated by the end of the stream.
+                None => return self.invalid(),
+            };
+
+            if !range.contains(&byte) {
+                // The byte does not continue this sequence. Report the maximal
+                // invalid prefix gathered so far and leave `byte` in the reader
+                // for the next decode, so recoverable input is not dropped.
+                return self.invalid();
+            }
+
+            bytes[i] = byte;
+            self.bump();
+        }
+
+        // The accepted ranges guarantee a well-formed sequence, so decoding
+        // cannot fail.
+        let s = std::str::from_utf8(&bytes[..len]).unwrap();
+        Some(Ok(s.chars().next().unwrap()))
+    }
+}
+
 /// Iterator over the characters of a [`Buffer`].
 ///
 /// This iterator is created using the [`Buffer::iter`] method or the
 /// [`Buffer::iter_from`] method. When it reaches the end of the buffer, the
 /// buffer will start reading from the source stream until the stream itself
 /// return `None`.
-pub struct Iter<'b, I: 'b + Iterator<Item = Result<char>>> {
-    buffer: &'b Buffer<I>,
+pub struct Iter<'b, I: 'b + Iterator<Item = Result<char>>, M: 'b + Metrics = DefaultMetrics> {
+    buffer: &'b Buffer<I, M>,
     i: Option<Result<usize>>,
     pos: Position,
     end: Position,
 }
 
-impl<'b, I: 'b + Iterator<Item = Result<char>>> Iter<'b, I> {
+impl<'b, I: 'b + Iterator<Item = Result<char>>, M: 'b + Metrics> Iter<'b, I, M> {
     pub fn into_string(self) -> Result<String> {
         let mut string = String::new();
 
@@ -262,7 +892,7 @@ impl<'b, I: 'b + Iterator<Item = Result<char>>> Iter<'b, I> {
     }
 }
 
-impl<'b, I: 'b + Iterator<Item = Result<char>>> Iterator for Iter<'b, I> {
+impl<'b, I: 'b + Iterator<Item = Result<char>>, M: 'b + Metrics> Iterator for Iter<'b, I, M> {
     type Item = Result<char>;
 
     fn next(&mut self) -> Option<Result<char>> {
@@ -273,7 +903,7 @@ impl<'b, I: 'b + Iterator<Item = Result<char>>> Iterator for Iter<'b, I> {
                 Some(Ok(ref mut i)) => {
                     match self.buffer.get(*i) {
                         Some(Ok(c)) => {
-                            self.pos = self.pos.next(c);
+                            self.pos = self.pos.next_metrics(c, &self.buffer.p.borrow().metrics);
                             *i += 1;
                             Some(Ok(c))
                         }
@@ -295,3 +925,177 @@ impl<'b, I: 'b + Iterator<Item = Result<char>>> Iterator for Iter<'b, I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DefaultMetrics, Position, Span};
+    use std::io::{BufReader, Cursor};
+
+    /// Decode a byte slice straight through the incremental decoder.
+    fn decode(bytes: &[u8], lossy: bool) -> Chars<Cursor<Vec<u8>>> {
+        Chars::new(Cursor::new(bytes.to_vec()), lossy)
+    }
+
+    #[test]
+    fn decode_lossy_resyncs_after_invalid() {
+        // `0xE2` starts a three-byte sequence but `0x28` ('(') is not a
+        // continuation byte: the decoder must keep the '(' and only replace
+        // the surrounding junk, like `String::from_utf8_lossy`.
+        let chars: Vec<char> = decode(&[0xE2, 0x28, 0xA1], true)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(chars, ['\u{FFFD}', '(', '\u{FFFD}']);
+    }
+
+    #[test]
+    fn decode_hard_fail_on_invalid() {
+        let mut chars = decode(&[0xE2, 0x28, 0xA1], false);
+        assert!(chars.next().unwrap().is_err());
+        // The iterator is fused once an error is surfaced.
+        assert!(chars.next().is_none());
+    }
+
+    #[test]
+    fn decode_truncated_sequence() {
+        // A lone lead byte at the end of the stream is a truncated sequence.
+        let lossy: Vec<char> = decode(&[0xE2], true).map(|r| r.unwrap()).collect();
+        assert_eq!(lossy, ['\u{FFFD}']);
+        assert!(decode(&[0xE2], false).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn decode_split_across_read_boundary() {
+        // A one-byte buffer hands the decoder a single byte per `fill_buf`, so
+        // the '€' (`E2 82 AC`) is held across three read boundaries.
+        let reader = BufReader::with_capacity(1, Cursor::new("a€b".as_bytes().to_vec()));
+        let chars: Vec<char> = Chars::new(reader, false).map(|r| r.unwrap()).collect();
+        assert_eq!(chars, ['a', '€', 'b']);
+    }
+
+    #[test]
+    fn from_read_decodes_incrementally() {
+        let reader = BufReader::with_capacity(1, Cursor::new("héllo\nwörld\n".as_bytes().to_vec()));
+        let buffer = Buffer::from_read(reader, Position::new(0, 0));
+        let text: String = buffer.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(text, "héllo\nwörld\n");
+    }
+
+    #[test]
+    fn resolve_zero_width_end_of_line_span() {
+        let buffer = Buffer::new("ab\ncd\n".chars().map(Ok), Position::new(0, 0));
+
+        // An empty span sitting at the end of the first line, after "ab".
+        let span: Span = Position::new(0, 2).into();
+        let resolved = buffer.resolve(span).unwrap();
+
+        assert_eq!(resolved.lines.len(), 1);
+        assert_eq!(resolved.lines[0].text, "ab");
+        assert_eq!(resolved.start_col, 2);
+        assert_eq!(resolved.end_col, 2);
+    }
+
+    #[test]
+    fn rewind_after_trim_yields_identical_chars() {
+        let source = "alpha\nbeta\ngamma\ndelta\n".to_string();
+        let factory = move |offset: usize| {
+            source[offset..].chars().collect::<Vec<_>>().into_iter().map(Ok::<char, std::io::Error>)
+        };
+
+        let buffer = Buffer::new_rewindable(factory, Position::new(0, 0), 2, DefaultMetrics);
+
+        // Read the whole stream: the two-line window trims the leading lines.
+        let forward: String = buffer.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(forward, "alpha\nbeta\ngamma\ndelta\n");
+
+        // The first line scrolled out of the window long ago; reading it back
+        // rewinds the factory and re-decodes it to the same characters.
+        let rewound: String = (0..5)
+            .map(|col| buffer.at(Position::new(0, col)).unwrap().unwrap())
+            .collect();
+        assert_eq!(rewound, "alpha");
+    }
+
+    /// Build a rewindable buffer over `source` with the given window.
+    fn windowed(source: &str, window: usize) -> Buffer<impl Iterator<Item = std::io::Result<char>>> {
+        let source = source.to_string();
+        let factory = move |offset: usize| {
+            source[offset..].chars().collect::<Vec<_>>().into_iter().map(Ok::<char, std::io::Error>)
+        };
+        Buffer::new_rewindable(factory, Position::new(0, 0), window, DefaultMetrics)
+    }
+
+    #[test]
+    fn iter_over_windowed_buffer_is_complete() {
+        // Plain forward iteration must read the whole stream even though the
+        // window trims leading lines and shifts `data` underneath the cursor.
+        let buffer = windowed("one\ntwo\nthree\nfour\nfive\n", 2);
+        let text: String = buffer.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(text, "one\ntwo\nthree\nfour\nfive\n");
+    }
+
+    #[test]
+    fn at_and_index_at_across_trim_boundary() {
+        let buffer = windowed("one\ntwo\nthree\nfour\n", 2);
+
+        // Drain the stream so the first lines are trimmed out of the window.
+        let _: String = buffer.iter().map(|r| r.unwrap()).collect();
+
+        // A character from a trimmed line is recovered through a rewind.
+        assert_eq!(buffer.at(Position::new(0, 0)).unwrap().unwrap(), 'o');
+
+        // The logical index is stable: resolving the same position twice
+        // yields the same index and the same character.
+        let i = buffer.index_at(Position::new(1, 0)).unwrap().unwrap();
+        let j = buffer.index_at(Position::new(1, 0)).unwrap().unwrap();
+        assert_eq!(i, j);
+        assert_eq!(buffer.at(Position::new(1, 0)).unwrap().unwrap(), 't');
+    }
+
+    #[test]
+    fn byte_span_maps_multibyte_chars() {
+        let source = "héllo";
+        let buffer = Buffer::new(source.chars().map(Ok), Position::new(0, 0));
+
+        // Span covering "hél" — the 'é' is two bytes wide.
+        let mut span: Span = Position::new(0, 0).into();
+        for c in "hél".chars() {
+            span.push(c, &DefaultMetrics);
+        }
+
+        let range = buffer.byte_span(span).unwrap();
+        assert_eq!(range, 0..4);
+        assert_eq!(&source[range], "hél");
+    }
+
+    #[test]
+    fn byte_span_reaches_end_of_input() {
+        let source = "hi";
+        let buffer = Buffer::new(source.chars().map(Ok), Position::new(0, 0));
+
+        let mut span: Span = Position::new(0, 0).into();
+        for c in source.chars() {
+            span.push(c, &DefaultMetrics);
+        }
+
+        // The span ends at the end of the stream, past the last character.
+        let range = buffer.byte_span(span).unwrap();
+        assert_eq!(range, 0..2);
+        assert_eq!(&source[range], "hi");
+    }
+
+    #[test]
+    fn byte_index_at_survives_trim_and_rewind() {
+        let buffer = windowed("alpha\nbeta\ngamma\n", 1);
+
+        // Drain the stream so only the last line stays in the window.
+        let _: String = buffer.iter().map(|r| r.unwrap()).collect();
+
+        // "gamma" starts at byte 11 ("alpha\n" = 6, "beta\n" = 5); still buffered.
+        assert_eq!(buffer.byte_index_at(Position::new(2, 0)).unwrap().unwrap(), 11);
+
+        // The very first character scrolled out, but a rewind recovers its
+        // byte offset unchanged.
+        assert_eq!(buffer.byte_index_at(Position::new(0, 0)).unwrap().unwrap(), 0);
+    }
+}
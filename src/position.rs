@@ -1,5 +1,81 @@
 use std::fmt;
 
+/// Cursor metrics.
+///
+/// A `Metrics` instance describes how characters advance the cursor while a
+/// source file is read. It is used by [`Position::next_metrics`] to decide how
+/// wide a tabulation is and how many columns a given [`char`] occupies.
+///
+/// The [`DefaultMetrics`] implementation reproduces the historical behavior of
+/// this crate (tab-stop every `8` columns, every character one column wide).
+/// The [`UnicodeMetrics`] implementation is aware of the East Asian Width of
+/// each character, so that the computed columns match what the user actually
+/// sees in a terminal.
+pub trait Metrics {
+    /// Width of a tabulation, in columns.
+    ///
+    /// A `\t` moves the cursor to the next column that is a multiple of this
+    /// value.
+    fn tab_stop(&self) -> usize;
+
+    /// Number of columns occupied by the given character.
+    ///
+    /// This is only called for printable characters: control characters are
+    /// handled directly by [`Position::next_metrics`].
+    fn char_width(&self, c: char) -> usize;
+}
+
+/// Default cursor metrics.
+///
+/// Tabulations stop every `8` columns and every printable character advances
+/// the cursor by a single column. This reproduces the behavior of
+/// [`Position::next`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct DefaultMetrics;
+
+impl DefaultMetrics {
+    /// Create a new default metrics instance.
+    #[must_use]
+    pub const fn new() -> Self { Self }
+}
+
+impl Metrics for DefaultMetrics {
+    fn tab_stop(&self) -> usize { 8 }
+
+    fn char_width(&self, _c: char) -> usize { 1 }
+}
+
+/// Unicode-aware cursor metrics.
+///
+/// Character widths are derived from the Unicode East Asian Width property:
+/// Wide and Fullwidth code points (CJK ideographs, most emoji, ...) advance the
+/// cursor by two columns, combining marks and zero-width characters advance it
+/// by none, and every other printable character advances it by one column.
+/// As for [`DefaultMetrics`], tabulations stop every `8` columns.
+///
+/// The widths come from the [`unicode-width`] crate, which must be declared as
+/// a dependency for this metrics implementation.
+///
+/// [`unicode-width`]: https://crates.io/crates/unicode-width
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct UnicodeMetrics;
+
+impl UnicodeMetrics {
+    /// Create a new unicode-aware metrics instance.
+    #[must_use]
+    pub const fn new() -> Self { Self }
+}
+
+impl Metrics for UnicodeMetrics {
+    fn tab_stop(&self) -> usize { 8 }
+
+    fn char_width(&self, c: char) -> usize {
+        // Non-printable characters are filtered out by `next_metrics` before
+        // reaching this point, so a missing width means a zero-width character.
+        unicode_width::UnicodeWidthChar::width(c).unwrap_or(0)
+    }
+}
+
 /// Position in a source file.
 ///
 /// This holds the line and column position of a character in a source file.
@@ -86,33 +162,47 @@ impl Position {
     ///
     /// ### Tabulations
     ///
-    /// The `\t` will move the cursor to the next horizontal tab-top.
+    /// The `\t` will move the cursor to the next horizontal tab-stop.
     /// This function assumes there is a tab-stop every 8 columns.
     /// Note that there is no standard on the size of a tabulation, however a length of 8 columns
     /// seems typical.
     ///
-    /// As of today, there is no way to use another tab length.
-    ///
-    /// I understand that this lacks of flexibility may become an issue in the near future,
-    /// and I will try to add this possibility. In the meantime, you are very welcome to contribute
-    /// if you need this feature right away.
+    /// Use [`next_metrics`](Position::next_metrics) with a custom [`Metrics`] instance to pick
+    /// another tab length.
     ///
     /// ## Full-width characters
     ///
-    /// As for now, double-width characters of full-width characters are *not* supported. They
-    /// will move the cursor by only one column as any other regular-width character. You are
-    /// welcome to contribute to handle them.
+    /// This method moves the cursor by one column for every printable character, regardless of
+    /// its actual width. Use [`next_metrics`](Position::next_metrics) with [`UnicodeMetrics`] to
+    /// account for double-width and full-width characters.
+    #[must_use]
+    pub fn next(&self, c: char) -> Self { self.next_metrics(c, &DefaultMetrics) }
+
+    /// Move to the position following the given [`char`], using the given [`Metrics`].
+    ///
+    /// This behaves like [`next`](Position::next) except that the tabulation length and the
+    /// width of each printable character are decided by `metrics`.
+    ///
+    /// New lines (`\n`) still reset the column and move to the next line, carriage returns
+    /// (`\r`) still reset the column, and any other control character is still treated as a
+    /// 0-width character with no semantics.
     #[must_use]
-    pub fn next(&self, c: char) -> Self {
+    pub fn next_metrics<M: Metrics + ?Sized>(&self, c: char, metrics: &M) -> Self {
         match c {
             '\n' => self.next_line(),
             '\r' => self.reset_column(),
-            '\t' => Self {
+            '\t' => {
+                let tab_stop = metrics.tab_stop();
+                Self {
+                    line: self.line,
+                    column: (self.column / tab_stop) * tab_stop + tab_stop,
+                }
+            }
+            c if c.is_control() => *self,
+            _ => Self {
                 line: self.line,
-                column: (self.column / 8) * 8 + 8,
+                column: self.column + metrics.char_width(c),
             },
-            c if c.is_control() => *self,
-            _ => self.next_column(),
         }
     }
 }
@@ -152,3 +242,46 @@ impl fmt::Debug for Position {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_metrics_tab_and_width() {
+        let m = DefaultMetrics;
+        // Every printable character is one column wide.
+        assert_eq!(Position::new(0, 0).next_metrics('a', &m), Position::new(0, 1));
+        // Tabulations land on the next multiple of 8.
+        assert_eq!(Position::new(0, 0).next_metrics('\t', &m), Position::new(0, 8));
+        assert_eq!(Position::new(0, 3).next_metrics('\t', &m), Position::new(0, 8));
+        assert_eq!(Position::new(0, 8).next_metrics('\t', &m), Position::new(0, 16));
+    }
+
+    #[test]
+    fn unicode_metrics_wide_and_zero_width() {
+        let m = UnicodeMetrics;
+
+        // A Fullwidth CJK ideograph advances the cursor by two columns.
+        assert_eq!(m.char_width('世'), 2);
+        assert_eq!(Position::new(0, 0).next_metrics('世', &m), Position::new(0, 2));
+
+        // A combining mark has no width.
+        assert_eq!(m.char_width('\u{0301}'), 0);
+        assert_eq!(Position::new(0, 1).next_metrics('\u{0301}', &m), Position::new(0, 1));
+
+        // Tabulations still stop every 8 columns.
+        assert_eq!(Position::new(0, 5).next_metrics('\t', &m), Position::new(0, 8));
+    }
+
+    #[test]
+    fn newlines_and_control_characters() {
+        let m = DefaultMetrics;
+        // `\n` moves to the next line and resets the column.
+        assert_eq!(Position::new(3, 5).next_metrics('\n', &m), Position::new(4, 0));
+        // `\r` resets the column on the same line.
+        assert_eq!(Position::new(3, 5).next_metrics('\r', &m), Position::new(3, 0));
+        // Other control characters are zero-width and move nothing.
+        assert_eq!(Position::new(3, 5).next_metrics('\u{0008}', &m), Position::new(3, 5));
+    }
+}